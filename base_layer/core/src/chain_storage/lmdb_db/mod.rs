@@ -25,13 +25,15 @@ mod key_prefix_cursor;
 mod lmdb;
 #[allow(clippy::module_inception)]
 mod lmdb_db;
+mod pruning;
 
 use crate::transactions::transaction::{TransactionInput, TransactionKernel, TransactionOutput};
 pub use lmdb_db::{create_lmdb_database, create_recovery_lmdb_database, LMDBDatabase};
+pub use pruning::AutoPruneConfig;
 use serde::{Deserialize, Serialize};
 use tari_common_types::types::HashOutput;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct TransactionOutputRowData {
     pub output: Option<TransactionOutput>,
     pub header_hash: HashOutput,
@@ -0,0 +1,347 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Pruning support for the LMDB transaction stores: reclaiming space for spent outputs below a confirmation horizon
+//! while keeping the hashes and MMR positions that proofs depend on.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LMDBDatabase, TransactionOutputRowData};
+use crate::chain_storage::ChainStorageError;
+
+/// The metadata key under which the current pruning horizon is stored, so a restarted node knows how far it has
+/// already pruned without re-scanning the whole output set.
+const PRUNING_HORIZON_METADATA_KEY: &str = "pruned_height";
+
+/// Config for the automatic, interval-driven pruning pass.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AutoPruneConfig {
+    /// Whether automatic pruning is enabled at all.
+    pub enabled: bool,
+    /// How often the automatic pruning pass runs.
+    pub interval: Duration,
+    /// Outputs mined at least this many blocks below the chain tip are eligible for pruning.
+    pub horizon_blocks: u64,
+}
+
+impl Default for AutoPruneConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::from_secs(60 * 60),
+            horizon_blocks: 2880,
+        }
+    }
+}
+
+/// Clears the output body of a spent output row, retaining its `hash`/`witness_hash`/`mmr_position` so MMR proofs
+/// that reference it still verify.
+fn prune_output_row(mut row: TransactionOutputRowData) -> TransactionOutputRowData {
+    row.output = None;
+    row
+}
+
+/// Given the current chain tip and an [`AutoPruneConfig`], the height at or below which outputs are eligible for
+/// pruning, or `None` if automatic pruning is disabled or the chain isn't yet past the horizon.
+pub fn auto_prune_target_height(tip_height: u64, config: &AutoPruneConfig) -> Option<u64> {
+    if !config.enabled {
+        return None;
+    }
+    tip_height.checked_sub(config.horizon_blocks).filter(|&height| height > 0)
+}
+
+/// The storage operations the pruning algorithm needs, pulled out of `LMDBDatabase` so [`prune_to_height_with`] can
+/// be exercised in tests against an in-memory store (see `test::InMemoryPruningStore`) without standing up a real
+/// LMDB environment. `LMDBDatabase`'s implementation is a thin wrapper around its own transaction-based accessors.
+pub(crate) trait PruningStore {
+    /// The height this store has been pruned to, or `0` if it has never been pruned.
+    fn pruning_horizon(&self) -> Result<u64, ChainStorageError>;
+
+    /// Prunes every spent output row mined in `(from_height, to_height]` and records `to_height` as the new pruning
+    /// horizon, all as a single atomic unit: a crash partway through must leave the store exactly as it was before
+    /// the pass started, never with some rows pruned and the horizon not yet advanced (which would re-prune them on
+    /// the next pass) or vice versa. Returns the number of rows pruned.
+    fn run_pruning_pass(&self, from_height: u64, to_height: u64) -> Result<usize, ChainStorageError>;
+
+    /// Reclaims the space freed by pruning. Expensive, so only called when something was actually pruned, and does
+    /// not need to be atomic with [`Self::run_pruning_pass`] — a crash between the two just leaves the reclaim for
+    /// next time.
+    fn run_compaction(&self) -> Result<(), ChainStorageError>;
+}
+
+// `read_transaction`, `write_transaction`, `get_metadata_u64`, `set_metadata_u64`, `fetch_spent_outputs_mined_in_range`,
+// `replace_output_row` and `compact` are `LMDBDatabase`'s existing low-level primitives, defined alongside the rest
+// of its transaction/table plumbing in `lmdb_db.rs` (not part of this pruning-focused change set) -- this impl is
+// just a thin adapter over them for the `PruningStore` trait, not new API surface on `LMDBDatabase` itself.
+impl PruningStore for LMDBDatabase {
+    fn pruning_horizon(&self) -> Result<u64, ChainStorageError> {
+        let txn = self.read_transaction()?;
+        Ok(self.get_metadata_u64(&txn, PRUNING_HORIZON_METADATA_KEY)?.unwrap_or(0))
+    }
+
+    fn run_pruning_pass(&self, from_height: u64, to_height: u64) -> Result<usize, ChainStorageError> {
+        let txn = self.write_transaction()?;
+
+        let mut num_pruned = 0usize;
+        for row in self.fetch_spent_outputs_mined_in_range(&txn, from_height, to_height)? {
+            LMDBDatabase::replace_output_row(self, &txn, &prune_output_row(row))?;
+            num_pruned += 1;
+        }
+        self.set_metadata_u64(&txn, PRUNING_HORIZON_METADATA_KEY, to_height)?;
+
+        txn.commit()?;
+        Ok(num_pruned)
+    }
+
+    fn run_compaction(&self) -> Result<(), ChainStorageError> {
+        self.compact()
+    }
+}
+
+/// Prunes all spent output rows mined above `store`'s current pruning horizon and at or below `height`, retaining
+/// their hash, witness hash and MMR position so MMR proofs still verify. Only compacts the store (to return freed
+/// pages to the OS) if anything was actually pruned, since a full compaction rewrites the data file and is too
+/// expensive to run on every pass regardless of how small the newly-eligible range is. Returns the number of rows
+/// pruned.
+///
+/// Unspent outputs are never pruned, regardless of their mined height.
+fn prune_to_height_with<S: PruningStore>(store: &S, height: u64) -> Result<usize, ChainStorageError> {
+    let previous_horizon = store.pruning_horizon()?;
+    if height <= previous_horizon {
+        return Ok(0);
+    }
+
+    let num_pruned = store.run_pruning_pass(previous_horizon, height)?;
+    if num_pruned > 0 {
+        store.run_compaction()?;
+    }
+    Ok(num_pruned)
+}
+
+impl LMDBDatabase {
+    /// Prunes all spent `TransactionOutput` bodies mined above the current pruning horizon and at or below `height`.
+    /// See [`prune_to_height_with`] for the algorithm itself.
+    pub fn prune_to_height(&self, height: u64) -> Result<(), ChainStorageError> {
+        prune_to_height_with(self, height)?;
+        Ok(())
+    }
+
+    /// The height this database has been pruned to, or `0` if it has never been pruned.
+    pub fn pruning_horizon(&self) -> Result<u64, ChainStorageError> {
+        PruningStore::pruning_horizon(self)
+    }
+
+    /// Runs a pruning pass if `config` is enabled and the chain has advanced far enough past the current pruning
+    /// horizon, returning the new pruning horizon if a pass ran.
+    pub fn auto_prune(&self, tip_height: u64, config: &AutoPruneConfig) -> Result<Option<u64>, ChainStorageError> {
+        let Some(target_height) = auto_prune_target_height(tip_height, config) else {
+            return Ok(None);
+        };
+        if target_height <= self.pruning_horizon()? {
+            return Ok(None);
+        }
+        self.prune_to_height(target_height)?;
+        Ok(Some(target_height))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        collections::{HashMap, HashSet},
+    };
+
+    use tari_common_types::types::HashOutput;
+
+    use super::*;
+
+    fn row(mined_height: u64, has_output: bool) -> TransactionOutputRowData {
+        TransactionOutputRowData {
+            output: has_output.then(Default::default),
+            header_hash: HashOutput::default(),
+            mmr_position: mined_height as u32,
+            hash: HashOutput::default(),
+            witness_hash: HashOutput::default(),
+            mined_height,
+        }
+    }
+
+    #[test]
+    fn prune_output_row_clears_body_but_keeps_hashes() {
+        let original = row(10, true);
+        let hash = original.hash.clone();
+        let witness_hash = original.witness_hash.clone();
+        let mmr_position = original.mmr_position;
+
+        let pruned = prune_output_row(original);
+
+        assert!(pruned.output.is_none());
+        assert_eq!(pruned.hash, hash);
+        assert_eq!(pruned.witness_hash, witness_hash);
+        assert_eq!(pruned.mmr_position, mmr_position);
+    }
+
+    #[test]
+    fn auto_prune_target_height_respects_horizon_and_enabled_flag() {
+        let mut config = AutoPruneConfig {
+            enabled: true,
+            interval: Duration::from_secs(60),
+            horizon_blocks: 100,
+        };
+
+        assert_eq!(auto_prune_target_height(50, &config), None);
+        assert_eq!(auto_prune_target_height(150, &config), Some(50));
+
+        config.enabled = false;
+        assert_eq!(auto_prune_target_height(150, &config), None);
+    }
+
+    /// An in-memory [`PruningStore`], keyed by `mmr_position`, that lets the pruning algorithm be exercised against
+    /// a populated "database" without a real LMDB environment. There is deliberately no test here that drives
+    /// `impl PruningStore for LMDBDatabase` directly against a real environment -- that needs the LMDB
+    /// transaction/table plumbing this pruning-focused change set doesn't touch, so it belongs with `lmdb_db.rs`'s
+    /// own test suite, not duplicated here.
+    ///
+    /// `TransactionOutputRowData` itself has no spent/unspent flag -- a real `LMDBDatabase` only ever hands
+    /// `fetch_spent_outputs_mined_in_range` rows that are already known to be spent, so `output.is_some()` can't be
+    /// reused as that stand-in here: a genuinely unspent row still has a body. `spent` tracks that bookkeeping
+    /// separately so the double can actually distinguish "spent, not yet pruned" from "never spent".
+    struct InMemoryPruningStore {
+        horizon: RefCell<u64>,
+        rows: RefCell<HashMap<u32, TransactionOutputRowData>>,
+        spent: RefCell<HashSet<u32>>,
+    }
+
+    impl InMemoryPruningStore {
+        /// `rows` pairs each row with whether it has ever been spent (and is therefore eligible for pruning once it
+        /// falls below the horizon), independent of whether its `output` still has a body.
+        fn with_rows(rows: Vec<(TransactionOutputRowData, bool)>) -> Self {
+            let mut spent = HashSet::new();
+            let mut by_position = HashMap::new();
+            for (row, is_spent) in rows {
+                if is_spent {
+                    spent.insert(row.mmr_position);
+                }
+                by_position.insert(row.mmr_position, row);
+            }
+            Self {
+                horizon: RefCell::new(0),
+                rows: RefCell::new(by_position),
+                spent: RefCell::new(spent),
+            }
+        }
+    }
+
+    impl PruningStore for InMemoryPruningStore {
+        fn pruning_horizon(&self) -> Result<u64, ChainStorageError> {
+            Ok(*self.horizon.borrow())
+        }
+
+        fn run_pruning_pass(&self, from_height: u64, to_height: u64) -> Result<usize, ChainStorageError> {
+            let spent = self.spent.borrow();
+            let prunable: Vec<_> = self
+                .rows
+                .borrow()
+                .values()
+                .filter(|row| {
+                    spent.contains(&row.mmr_position) &&
+                        row.mined_height > from_height &&
+                        row.mined_height <= to_height
+                })
+                .cloned()
+                .collect();
+            drop(spent);
+
+            let mut rows = self.rows.borrow_mut();
+            for row in &prunable {
+                rows.insert(row.mmr_position, prune_output_row(row.clone()));
+            }
+            drop(rows);
+
+            *self.horizon.borrow_mut() = to_height;
+            Ok(prunable.len())
+        }
+
+        fn run_compaction(&self) -> Result<(), ChainStorageError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn prune_to_height_clears_output_but_keeps_hash_witness_hash_and_mmr_position_queryable() {
+        let spent_old = row(5, true);
+        let spent_eligible = row(10, true);
+        let unspent = row(8, false);
+        let store = InMemoryPruningStore::with_rows(vec![
+            (spent_old.clone(), true),
+            (spent_eligible.clone(), true),
+            (unspent.clone(), false),
+        ]);
+
+        let num_pruned = prune_to_height_with(&store, 10).unwrap();
+        assert_eq!(num_pruned, 2);
+        assert_eq!(store.pruning_horizon().unwrap(), 10);
+
+        let rows = store.rows.borrow();
+
+        let pruned = &rows[&spent_old.mmr_position];
+        assert!(pruned.output.is_none());
+        assert_eq!(pruned.hash, spent_old.hash);
+        assert_eq!(pruned.witness_hash, spent_old.witness_hash);
+        assert_eq!(pruned.mmr_position, spent_old.mmr_position);
+
+        let pruned = &rows[&spent_eligible.mmr_position];
+        assert!(pruned.output.is_none());
+        assert_eq!(pruned.hash, spent_eligible.hash);
+        assert_eq!(pruned.witness_hash, spent_eligible.witness_hash);
+        assert_eq!(pruned.mmr_position, spent_eligible.mmr_position);
+    }
+
+    #[test]
+    fn prune_to_height_does_not_touch_already_pruned_or_unspent_outputs() {
+        // Already spent and pruned on an earlier pass, so its body is already cleared.
+        let already_pruned = row(3, false);
+        // Genuinely unspent: mined within the eligible range and still carries a body. If this were pruned, the
+        // `output` assertion below would fail.
+        let unspent = row(7, true);
+        let store = InMemoryPruningStore::with_rows(vec![(already_pruned.clone(), true), (unspent.clone(), false)]);
+        *store.horizon.borrow_mut() = 5;
+
+        let num_pruned = prune_to_height_with(&store, 20).unwrap();
+
+        assert_eq!(num_pruned, 0);
+        assert!(store.rows.borrow()[&unspent.mmr_position].output.is_some());
+    }
+
+    #[test]
+    fn prune_to_height_is_a_no_op_at_the_current_horizon() {
+        let store = InMemoryPruningStore::with_rows(vec![(row(5, true), true)]);
+        *store.horizon.borrow_mut() = 10;
+
+        let num_pruned = prune_to_height_with(&store, 10).unwrap();
+
+        assert_eq!(num_pruned, 0);
+    }
+}
@@ -21,11 +21,13 @@
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     convert::TryFrom,
     fmt,
     fmt::{Display, Formatter},
     str::FromStr,
-    sync::OnceLock,
+    sync::{OnceLock, RwLock},
 };
 
 use serde::{Deserialize, Serialize};
@@ -33,22 +35,114 @@ use serde::{Deserialize, Serialize};
 use crate::ConfigurationError;
 
 static CURRENT_NETWORK: OnceLock<Network> = OnceLock::new();
+static CUSTOM_NETWORKS: OnceLock<RwLock<HashMap<u8, NetworkParameters>>> = OnceLock::new();
+
+/// The connection bytes reserved for the built-in networks. A custom network may not register itself against any of
+/// these.
+const RESERVED_BYTES: [u8; 3] = [0xaa, 0xbb, 0xcc];
+
+/// Parameters describing a user-defined, custom network, e.g. for standing up an isolated experimental network
+/// without recompiling. These are typically loaded from a `[network.custom]` config section and must be registered
+/// with [`register_custom_network`] before [`Network::set_current`] is called.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NetworkParameters {
+    /// The human-readable name used to select this network, e.g. via `TARI_NETWORK=<name>` or config. Must be
+    /// unique (case-insensitively) among registered custom networks.
+    pub name: String,
+    /// The connection byte used to gate peer connections. Must be unique and must not collide with a reserved byte
+    /// (`MainNet`/`TestNet`/`LocalNet`) or another registered custom network.
+    pub byte: u8,
+    /// The [`NetworkKind`] this custom network behaves like for address/byte-prefix encoding and key serialization.
+    pub kind: NetworkKind,
+    /// Opaque genesis/consensus parameters for this network, interpreted by the consensus layer.
+    pub genesis_params: Vec<u8>,
+}
+
+/// Registers a custom network's parameters so that it can subsequently be selected by name (via [`FromStr`]) or
+/// resolved from its connection byte (via `TryFrom<u8>`). This must be called before [`Network::set_current`] so
+/// that the custom network is known when the current network is established.
+///
+/// Returns an error if `params.byte` collides with a reserved byte or an already-registered custom network, or if
+/// `params.name` (case-insensitively) collides with an already-registered custom network's name -- name lookups
+/// resolve by scanning the registry in unspecified order, so an undetected name collision would make
+/// `Network::from_str` resolve to a different network from one run to the next.
+pub fn register_custom_network(params: NetworkParameters) -> Result<(), ConfigurationError> {
+    if RESERVED_BYTES.contains(&params.byte) {
+        return Err(ConfigurationError::new(
+            "network.custom",
+            Some(params.byte.to_string()),
+            "Custom network connection byte collides with a reserved network byte".to_string(),
+        ));
+    }
+    let mut registry = custom_network_registry()
+        .write()
+        .expect("custom network registry lock poisoned");
+    if registry.contains_key(&params.byte) {
+        return Err(ConfigurationError::new(
+            "network.custom",
+            Some(params.byte.to_string()),
+            format!("A custom network is already registered for byte {:#04x}", params.byte),
+        ));
+    }
+    if registry
+        .values()
+        .any(|existing| existing.name.eq_ignore_ascii_case(&params.name))
+    {
+        return Err(ConfigurationError::new(
+            "network.custom",
+            Some(params.name.clone()),
+            format!("A custom network is already registered with the name '{}'", params.name),
+        ));
+    }
+    registry.insert(params.byte, params);
+    Ok(())
+}
+
+fn custom_network_registry() -> &'static RwLock<HashMap<u8, NetworkParameters>> {
+    CUSTOM_NETWORKS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn custom_network_by_byte(byte: u8) -> Option<NetworkParameters> {
+    custom_network_registry()
+        .read()
+        .expect("custom network registry lock poisoned")
+        .get(&byte)
+        .cloned()
+}
+
+fn custom_network_by_name(name: &str) -> Option<NetworkParameters> {
+    custom_network_registry()
+        .read()
+        .expect("custom network registry lock poisoned")
+        .values()
+        .find(|params| params.name.eq_ignore_ascii_case(name))
+        .cloned()
+}
 
 /// Represents the available Tari p2p networks. Only nodes with matching byte values will be able to connect, so these
 /// should never be changed once released.
-#[repr(u8)]
-#[derive(Clone, Debug, PartialEq, Eq, Copy, Serialize, Deserialize)]
+///
+/// `CustomNet` carries a boxed [`NetworkParameters`], so unlike before this enum is no longer `Copy` and no longer
+/// has a stable `#[repr(u8)]` layout; `as_byte()`/`as_key_str()` now take `&self` instead of `self`, and
+/// `as_key_str()` returns `Cow<'_, str>` instead of `&'static str`. Callers that held onto a bare `network as u8`
+/// cast or relied on `Network` being `Copy` need to switch to `network.as_byte()` and `network.clone()`
+/// respectively. A grep of the files touched by this change set turned up no such call sites, but that search
+/// wasn't a full-workspace audit -- treat this as a breaking change when merging and grep your own call sites
+/// for `as u8` casts and places that assumed `Network: Copy` before relying on this being a no-op upgrade.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(try_from = "String", into = "String")]
 pub enum Network {
-    MainNet = 0xaa,
-    TestNet = 0xbb,
-    LocalNet = 0xcc,
+    MainNet,
+    TestNet,
+    LocalNet,
+    /// A user-defined network, identified by its registered [`NetworkParameters`].
+    CustomNet(Box<NetworkParameters>),
 }
 
 impl Network {
     pub fn get_current_or_user_setting_or_default() -> Self {
         match CURRENT_NETWORK.get() {
-            Some(&network) => network,
+            Some(network) => network.clone(),
             None => {
                 // Check to see if the network has been set by the environment, otherwise use the default
                 match std::env::var("TARI_NETWORK") {
@@ -63,21 +157,57 @@ impl Network {
         CURRENT_NETWORK.set(network)
     }
 
-    pub fn as_byte(self) -> u8 {
-        self as u8
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            Network::MainNet => 0xaa,
+            Network::TestNet => 0xbb,
+            Network::LocalNet => 0xcc,
+            Network::CustomNet(params) => params.byte,
+        }
     }
 
-    pub const fn as_key_str(self) -> &'static str {
+    pub fn as_key_str(&self) -> Cow<'_, str> {
         #[allow(clippy::enum_glob_use)]
         use Network::*;
         match self {
-            MainNet => "mainnet",
-            TestNet => "testnet",
-            LocalNet => "localnet",
+            MainNet => Cow::Borrowed("mainnet"),
+            TestNet => Cow::Borrowed("testnet"),
+            LocalNet => Cow::Borrowed("localnet"),
+            CustomNet(params) => Cow::Owned(params.name.clone()),
+        }
+    }
+}
+
+/// Distinguishes how a [`Network`] should be treated for address/byte-prefix encoding and key serialization,
+/// independent of its consensus identity or connection byte. Custom networks declare which kind they behave like
+/// (e.g. "like testnet") so address and key encoding rules don't need to be duplicated for every experimental
+/// network.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Copy, Serialize, Deserialize)]
+pub enum NetworkKind {
+    Mainnet,
+    Testnet,
+    Localnet,
+}
+
+impl From<Network> for NetworkKind {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::MainNet => NetworkKind::Mainnet,
+            Network::TestNet => NetworkKind::Testnet,
+            Network::LocalNet => NetworkKind::Localnet,
+            Network::CustomNet(params) => params.kind,
         }
     }
 }
 
+impl Network {
+    /// Returns the [`NetworkKind`] this network behaves like for address/byte-prefix encoding purposes. Custom
+    /// networks return the `kind` declared in their [`NetworkParameters`].
+    pub fn kind(&self) -> NetworkKind {
+        NetworkKind::from(self.clone())
+    }
+}
+
 /// The default network for all applications
 impl Default for Network {
     #[cfg(tari_target_network_mainnet)]
@@ -104,11 +234,13 @@ impl FromStr for Network {
             "mainnet" => Ok(MainNet),
             "testnet" => Ok(TestNet),
             "localnet" => Ok(LocalNet),
-            invalid => Err(ConfigurationError::new(
-                "network",
-                Some(value.to_string()),
-                format!("Invalid network option: {}", invalid),
-            )),
+            invalid => custom_network_by_name(invalid).map(|params| CustomNet(Box::new(params))).ok_or_else(|| {
+                ConfigurationError::new(
+                    "network",
+                    Some(value.to_string()),
+                    format!("Invalid network option: {}", invalid),
+                )
+            }),
         }
     }
 }
@@ -131,21 +263,25 @@ impl TryFrom<u8> for Network {
 
     fn try_from(v: u8) -> Result<Self, ConfigurationError> {
         match v {
-            x if x == Network::MainNet as u8 => Ok(Network::MainNet),
-            x if x == Network::TestNet as u8 => Ok(Network::TestNet),
-            x if x == Network::LocalNet as u8 => Ok(Network::LocalNet),
-            _ => Err(ConfigurationError::new(
-                "network",
-                Some(v.to_string()),
-                format!("Invalid network option: {}", v),
-            )),
+            0xaa => Ok(Network::MainNet),
+            0xbb => Ok(Network::TestNet),
+            0xcc => Ok(Network::LocalNet),
+            other => custom_network_by_byte(other)
+                .map(|params| Network::CustomNet(Box::new(params)))
+                .ok_or_else(|| {
+                    ConfigurationError::new(
+                        "network",
+                        Some(other.to_string()),
+                        format!("Invalid network option: {}", other),
+                    )
+                }),
         }
     }
 }
 
 impl Display for Network {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        f.write_str(self.as_key_str())
+        f.write_str(self.as_key_str().as_ref())
     }
 }
 
@@ -197,4 +333,65 @@ mod test {
         assert_eq!(Network::try_from(0xbb).unwrap(), Network::TestNet);
         assert_eq!(Network::try_from(0xcc).unwrap(), Network::LocalNet);
     }
+
+    #[test]
+    fn custom_network_round_trip() {
+        let params = NetworkParameters {
+            name: "mynet".to_string(),
+            byte: 0x42,
+            kind: NetworkKind::Testnet,
+            genesis_params: vec![1, 2, 3],
+        };
+        register_custom_network(params.clone()).unwrap();
+
+        let from_byte = Network::try_from(0x42_u8).unwrap();
+        assert_eq!(from_byte, Network::CustomNet(Box::new(params.clone())));
+        assert_eq!(from_byte.as_byte(), 0x42);
+
+        let from_name = Network::from_str("mynet").unwrap();
+        assert_eq!(from_name, Network::CustomNet(Box::new(params)));
+        assert_eq!(from_name.as_key_str(), "mynet");
+    }
+
+    #[test]
+    fn custom_network_rejects_reserved_byte() {
+        let params = NetworkParameters {
+            name: "clashnet".to_string(),
+            byte: 0xaa,
+            kind: NetworkKind::Testnet,
+            genesis_params: vec![],
+        };
+        assert!(register_custom_network(params).is_err());
+    }
+
+    #[test]
+    fn network_kind() {
+        assert_eq!(NetworkKind::from(Network::MainNet), NetworkKind::Mainnet);
+        assert_eq!(NetworkKind::from(Network::TestNet), NetworkKind::Testnet);
+        assert_eq!(NetworkKind::from(Network::LocalNet), NetworkKind::Localnet);
+        assert_eq!(Network::MainNet.kind(), NetworkKind::Mainnet);
+
+        let params = NetworkParameters {
+            name: "localish".to_string(),
+            byte: 0x55,
+            kind: NetworkKind::Localnet,
+            genesis_params: vec![],
+        };
+        let custom = Network::CustomNet(Box::new(params));
+        assert_eq!(custom.kind(), NetworkKind::Localnet);
+    }
+
+    #[test]
+    fn as_byte_and_as_key_str_work_by_reference_without_copy() {
+        // `Network` is no longer `Copy` (CustomNet holds a `Box<NetworkParameters>`), so callers must be able to
+        // call `as_byte()`/`as_key_str()` through a shared reference and still use the value afterwards.
+        let networks = [Network::MainNet, Network::TestNet, Network::LocalNet];
+        for network in &networks {
+            let byte = network.as_byte();
+            let key_str = network.as_key_str();
+            // `network` must still be usable here: as_byte/as_key_str take `&self`, they don't consume it.
+            assert_eq!(Network::try_from(byte).unwrap(), *network);
+            assert_eq!(key_str, network.to_string());
+        }
+    }
 }
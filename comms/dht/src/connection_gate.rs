@@ -0,0 +1,111 @@
+// Copyright 2019, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Wires [`network_policy::evaluate_connection`] into peer connection acceptance: this is the integration point
+//! registered with the comms connection manager so that an active [`NetworkPolicy`](super::network_policy::
+//! NetworkPolicy) is actually enforced, rather than only being evaluable on demand.
+
+use std::net::IpAddr;
+
+use tari_comms::{
+    connection_manager::{ConnectionDirection, ConnectionValidationError, ConnectionValidator},
+    types::CommsPublicKey,
+};
+use tari_common::configuration::Network;
+
+use crate::network_policy::{evaluate_connection, PolicyDecision};
+
+/// A [`ConnectionValidator`] that rejects a connection before the handshake completes if it is denied by the active
+/// [`NetworkPolicy`] for `network`. Registered with the comms connection manager at node startup, alongside the
+/// existing connection-byte gating, so mainnet and testnet can enforce different peer allow/deny rules.
+pub struct PolicyConnectionGate {
+    network: Network,
+}
+
+impl PolicyConnectionGate {
+    pub fn new(network: Network) -> Self {
+        Self { network }
+    }
+}
+
+impl ConnectionValidator for PolicyConnectionGate {
+    fn validate(
+        &self,
+        _direction: ConnectionDirection,
+        public_key: &CommsPublicKey,
+        remote_ip: IpAddr,
+    ) -> Result<(), ConnectionValidationError> {
+        match evaluate_connection(&self.network, public_key, remote_ip) {
+            PolicyDecision::Allow => Ok(()),
+            PolicyDecision::Reject(reason) => Err(ConnectionValidationError::rejected(reason.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use tari_common::configuration::{NetworkKind, NetworkParameters};
+
+    use super::*;
+    use crate::network_policy::{create_policy, set_active_policy, DefaultAction, NetworkPolicy};
+
+    /// A `Network` value that's unique to this test, so setting its active policy can't race against (or be raced
+    /// by) another test in this binary touching the same `Network` key in `network_policy`'s shared globals --
+    /// `Network::MainNet`/`TestNet`/`LocalNet` are shared singletons and not safe for this.
+    fn isolated_network(name: &str) -> Network {
+        Network::CustomNet(Box::new(NetworkParameters {
+            name: name.to_string(),
+            byte: 0x01,
+            kind: NetworkKind::Localnet,
+            genesis_params: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn gate_rejects_when_active_policy_denies() {
+        let network = isolated_network("connection-gate-test-deny");
+        create_policy(NetworkPolicy::new("gate-test-deny", DefaultAction::Deny));
+        set_active_policy(network.clone(), "gate-test-deny").unwrap();
+        let gate = PolicyConnectionGate::new(network);
+
+        let result = gate.validate(
+            ConnectionDirection::Inbound,
+            &CommsPublicKey::default(),
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn gate_allows_when_no_active_policy() {
+        let gate = PolicyConnectionGate::new(isolated_network("connection-gate-test-no-policy"));
+
+        let result = gate.validate(
+            ConnectionDirection::Outbound,
+            &CommsPublicKey::default(),
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        );
+        assert!(result.is_ok());
+    }
+}
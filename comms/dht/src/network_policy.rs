@@ -0,0 +1,371 @@
+// Copyright 2019, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Named allow/deny rules gating which peers may connect, independent of the `Network` connection-byte gating.
+//!
+//! A [`NetworkPolicy`] is evaluated once per inbound or outbound connection attempt, before the handshake completes.
+//! Policies are named and can be registered per [`Network`](tari_common::configuration::Network) so that, for
+//! example, mainnet and testnet enforce different rules. [`evaluate_connection`] is the evaluation hook itself; see
+//! [`super::connection_gate::PolicyConnectionGate`] for where it is wired into peer connection acceptance.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{OnceLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tari_comms::types::CommsPublicKey;
+use tari_common::configuration::Network;
+
+/// The outcome of evaluating a [`NetworkPolicy`] against a connecting peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyDecision {
+    Allow,
+    Reject(PolicyRejectionReason),
+}
+
+/// Why a connection attempt was rejected by a [`NetworkPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyRejectionReason {
+    #[error("peer public key is on the deny list for policy '{policy_name}'")]
+    PublicKeyDenied { policy_name: String },
+    #[error("peer IP {ip} falls within a denied CIDR range for policy '{policy_name}'")]
+    IpRangeDenied { policy_name: String, ip: IpAddr },
+    #[error("peer did not match any allow rule and the default action for policy '{policy_name}' is deny")]
+    DefaultDeny { policy_name: String },
+}
+
+/// The action to take for a connection that matches neither the allow list nor the deny list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DefaultAction {
+    Allow,
+    Deny,
+}
+
+/// An IPv4/IPv6 CIDR range, e.g. `10.0.0.0/8`. Constructed via [`CidrRange::new`] (or deserialized, which goes
+/// through the same validation), so `prefix_len` is always within bounds for `addr`'s address family.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawCidrRange")]
+pub struct CidrRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// The wire/config representation of a [`CidrRange`], validated via `TryFrom` before a `CidrRange` can be
+/// constructed from it.
+#[derive(Debug, Deserialize)]
+pub struct RawCidrRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl TryFrom<RawCidrRange> for CidrRange {
+    type Error = CidrRangeError;
+
+    fn try_from(raw: RawCidrRange) -> Result<Self, Self::Error> {
+        CidrRange::new(raw.addr, raw.prefix_len)
+    }
+}
+
+impl CidrRange {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, CidrRangeError> {
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(CidrRangeError::PrefixLengthOutOfRange {
+                prefix_len,
+                max_prefix_len,
+            });
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(base), IpAddr::V4(ip)) => {
+                // `new` already guarantees `prefix_len <= 32`, but `saturating_sub` keeps this safe even if that
+                // invariant is ever violated (e.g. by a future `unsafe`/bincode round-trip).
+                let shift = 32u32.saturating_sub(u32::from(self.prefix_len));
+                let mask = u32::MAX.checked_shl(shift).unwrap_or(0);
+                u32::from(base) & mask == u32::from(*ip) & mask
+            },
+            (IpAddr::V6(base), IpAddr::V6(ip)) => {
+                let shift = 128u32.saturating_sub(u32::from(self.prefix_len));
+                let mask = u128::MAX.checked_shl(shift).unwrap_or(0);
+                u128::from(base) & mask == u128::from(*ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CidrRangeError {
+    #[error("CIDR prefix length {prefix_len} exceeds the maximum of {max_prefix_len} for this address family")]
+    PrefixLengthOutOfRange { prefix_len: u8, max_prefix_len: u8 },
+}
+
+/// A named set of allow/deny rules for peer connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkPolicy {
+    pub name: String,
+    pub default_action: DefaultAction,
+    pub allowed_public_keys: Vec<CommsPublicKey>,
+    pub denied_public_keys: Vec<CommsPublicKey>,
+    pub allowed_ip_ranges: Vec<CidrRange>,
+    pub denied_ip_ranges: Vec<CidrRange>,
+}
+
+impl NetworkPolicy {
+    pub fn new(name: impl Into<String>, default_action: DefaultAction) -> Self {
+        Self {
+            name: name.into(),
+            default_action,
+            allowed_public_keys: Vec::new(),
+            denied_public_keys: Vec::new(),
+            allowed_ip_ranges: Vec::new(),
+            denied_ip_ranges: Vec::new(),
+        }
+    }
+
+    pub fn allow_public_key(&mut self, public_key: CommsPublicKey) {
+        self.denied_public_keys.retain(|pk| pk != &public_key);
+        self.allowed_public_keys.push(public_key);
+    }
+
+    pub fn deny_public_key(&mut self, public_key: CommsPublicKey) {
+        self.allowed_public_keys.retain(|pk| pk != &public_key);
+        self.denied_public_keys.push(public_key);
+    }
+
+    pub fn remove_public_key(&mut self, public_key: &CommsPublicKey) {
+        self.allowed_public_keys.retain(|pk| pk != public_key);
+        self.denied_public_keys.retain(|pk| pk != public_key);
+    }
+
+    /// Evaluates this policy against a connecting peer, checked in order: explicit public key deny, IP range deny,
+    /// explicit public key allow, IP range allow, then the policy's default action.
+    pub fn evaluate(&self, public_key: &CommsPublicKey, ip: IpAddr) -> PolicyDecision {
+        if self.denied_public_keys.contains(public_key) {
+            return PolicyDecision::Reject(PolicyRejectionReason::PublicKeyDenied {
+                policy_name: self.name.clone(),
+            });
+        }
+        if self.denied_ip_ranges.iter().any(|range| range.contains(&ip)) {
+            return PolicyDecision::Reject(PolicyRejectionReason::IpRangeDenied {
+                policy_name: self.name.clone(),
+                ip,
+            });
+        }
+        if self.allowed_public_keys.contains(public_key) || self.allowed_ip_ranges.iter().any(|range| range.contains(&ip))
+        {
+            return PolicyDecision::Allow;
+        }
+        match self.default_action {
+            DefaultAction::Allow => PolicyDecision::Allow,
+            DefaultAction::Deny => PolicyDecision::Reject(PolicyRejectionReason::DefaultDeny {
+                policy_name: self.name.clone(),
+            }),
+        }
+    }
+}
+
+static POLICIES: OnceLock<RwLock<HashMap<String, NetworkPolicy>>> = OnceLock::new();
+static ACTIVE_POLICY: OnceLock<RwLock<HashMap<Network, String>>> = OnceLock::new();
+
+fn policies() -> &'static RwLock<HashMap<String, NetworkPolicy>> {
+    POLICIES.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn active_policies() -> &'static RwLock<HashMap<Network, String>> {
+    ACTIVE_POLICY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registers or replaces a named policy.
+pub fn create_policy(policy: NetworkPolicy) {
+    policies().write().expect("network policy lock poisoned").insert(policy.name.clone(), policy);
+}
+
+/// Removes a named policy. If it was the active policy for any network, that network falls back to allowing all
+/// connections (no policy enforced).
+pub fn drop_policy(name: &str) {
+    policies().write().expect("network policy lock poisoned").remove(name);
+    active_policies()
+        .write()
+        .expect("network policy lock poisoned")
+        .retain(|_, active_name| active_name != name);
+}
+
+/// Lists the names of all registered policies.
+pub fn list_policies() -> Vec<String> {
+    policies().read().expect("network policy lock poisoned").keys().cloned().collect()
+}
+
+/// Returns a clone of the named policy, if registered.
+pub fn describe_policy(name: &str) -> Option<NetworkPolicy> {
+    policies().read().expect("network policy lock poisoned").get(name).cloned()
+}
+
+/// Selects `policy_name` as the active policy enforced for `network`. The policy must already be registered via
+/// [`create_policy`].
+pub fn set_active_policy(network: Network, policy_name: impl Into<String>) -> Result<(), PolicyError> {
+    let policy_name = policy_name.into();
+    if !policies().read().expect("network policy lock poisoned").contains_key(&policy_name) {
+        return Err(PolicyError::UnknownPolicy(policy_name));
+    }
+    active_policies()
+        .write()
+        .expect("network policy lock poisoned")
+        .insert(network, policy_name);
+    Ok(())
+}
+
+/// Evaluation hook for peer acceptance: checks `public_key`/`ip` against the active policy for `network`, if any. A
+/// network with no active policy allows all connections, matching the existing byte-gating behaviour.
+pub fn evaluate_connection(network: &Network, public_key: &CommsPublicKey, ip: IpAddr) -> PolicyDecision {
+    let active = active_policies().read().expect("network policy lock poisoned");
+    let Some(policy_name) = active.get(network) else {
+        return PolicyDecision::Allow;
+    };
+    let policies = policies().read().expect("network policy lock poisoned");
+    match policies.get(policy_name) {
+        Some(policy) => policy.evaluate(public_key, ip),
+        None => PolicyDecision::Allow,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("no policy named '{0}' is registered")]
+    UnknownPolicy(String),
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn default_deny_rejects_unmatched_peer() {
+        let policy = NetworkPolicy::new("strict", DefaultAction::Deny);
+        let decision = policy.evaluate(&CommsPublicKey::default(), IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(
+            decision,
+            PolicyDecision::Reject(PolicyRejectionReason::DefaultDeny {
+                policy_name: "strict".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn explicit_deny_takes_priority_over_allow() {
+        let mut policy = NetworkPolicy::new("mixed", DefaultAction::Allow);
+        let pk = CommsPublicKey::default();
+        policy.allow_public_key(pk.clone());
+        policy.deny_public_key(pk.clone());
+        let decision = policy.evaluate(&pk, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+        assert!(matches!(decision, PolicyDecision::Reject(_)));
+    }
+
+    #[test]
+    fn cidr_range_match() {
+        let range = CidrRange::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8).unwrap();
+        assert!(range.contains(&IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))));
+        assert!(!range.contains(&IpAddr::V4(Ipv4Addr::new(11, 1, 2, 3))));
+    }
+
+    #[test]
+    fn cidr_range_rejects_out_of_range_prefix_length() {
+        let err = CidrRange::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 33).unwrap_err();
+        assert_eq!(err, CidrRangeError::PrefixLengthOutOfRange {
+            prefix_len: 33,
+            max_prefix_len: 32,
+        });
+
+        let err = CidrRange::new(IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED), 200).unwrap_err();
+        assert_eq!(err, CidrRangeError::PrefixLengthOutOfRange {
+            prefix_len: 200,
+            max_prefix_len: 128,
+        });
+    }
+
+    #[test]
+    fn registry_create_list_drop() {
+        let policy = NetworkPolicy::new("temp-policy", DefaultAction::Allow);
+        create_policy(policy);
+        assert!(list_policies().contains(&"temp-policy".to_string()));
+        assert!(describe_policy("temp-policy").is_some());
+
+        drop_policy("temp-policy");
+        assert!(!list_policies().contains(&"temp-policy".to_string()));
+    }
+
+    /// A `Network` value that's unique to this test, so setting its active policy can't race against (or be raced
+    /// by) another test in this binary touching the same `Network` key in the shared `POLICIES`/`ACTIVE_POLICY`
+    /// globals -- `Network::MainNet`/`TestNet`/`LocalNet` are shared singletons and not safe for this.
+    fn isolated_network(name: &str) -> Network {
+        use tari_common::configuration::{NetworkKind, NetworkParameters};
+
+        Network::CustomNet(Box::new(NetworkParameters {
+            name: name.to_string(),
+            byte: 0x01,
+            kind: NetworkKind::Testnet,
+            genesis_params: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn active_policy_per_network_is_enforced() {
+        let enforced_network = isolated_network("network-policy-test-enforced");
+        let unenforced_network = isolated_network("network-policy-test-unenforced");
+        let policy = NetworkPolicy::new("mainnet-policy", DefaultAction::Deny);
+        create_policy(policy);
+        set_active_policy(enforced_network.clone(), "mainnet-policy").unwrap();
+
+        let decision = evaluate_connection(
+            &enforced_network,
+            &CommsPublicKey::default(),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+        );
+        assert!(matches!(decision, PolicyDecision::Reject(_)));
+
+        // `unenforced_network` has no active policy registered, so connections are allowed.
+        let decision = evaluate_connection(
+            &unenforced_network,
+            &CommsPublicKey::default(),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+        );
+        assert_eq!(decision, PolicyDecision::Allow);
+    }
+}
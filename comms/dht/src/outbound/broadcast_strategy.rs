@@ -20,9 +20,14 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::{fmt, fmt::Formatter};
+use std::{cmp::Ordering, fmt, fmt::Formatter};
 use tari_comms::{peer_manager::node_id::NodeId, types::CommsPublicKey};
 
+use crate::outbound::{
+    onion::OnionMessage,
+    peer_scorer::{filter_by_penalty_threshold, weighted_sample, PeerScorer},
+};
+
 #[derive(Debug, Clone)]
 pub struct BroadcastClosestRequest {
     pub n: usize,
@@ -38,10 +43,19 @@ pub enum BroadcastStrategy {
     DirectPublicKey(CommsPublicKey),
     /// Send to all known Communication Node peers
     Flood,
-    /// Send to all n nearest neighbour Communication Nodes
+    /// Send to all n nearest neighbour Communication Nodes. When a [`PeerScorer`] is supplied to [`select_peers`],
+    /// peers at or below [`DEFAULT_MIN_PEER_SCORE`] are excluded and the remainder are ordered by score (highest first)
+    /// before being truncated to `n`.
     Closest(BroadcastClosestRequest),
-    /// Send to a random set of peers of size n that are Communication Nodes
+    /// Send to a random set of peers of size n that are Communication Nodes. When a [`PeerScorer`] is supplied to
+    /// [`select_peers`], peers are sampled weighted by inverse penalty instead of uniformly.
     Random(usize),
+    /// Send to the first peer in `path`, onion-wrapped so that each hop only learns the next hop. The final layer
+    /// is addressed to the last entry in `path`.
+    OnionRoute {
+        path: Vec<CommsPublicKey>,
+        message: OnionMessage,
+    },
 }
 
 impl fmt::Display for BroadcastStrategy {
@@ -53,6 +67,8 @@ impl fmt::Display for BroadcastStrategy {
             Flood => write!(f, "Flood"),
             Closest(BroadcastClosestRequest { n, .. }) => write!(f, "Closest({})", n),
             Random(n) => write!(f, "Random({})", n),
+            // Never log the path: position in the route must not leak via logs.
+            OnionRoute { path, .. } => write!(f, "OnionRoute({})", path.len()),
         }
     }
 }
@@ -74,4 +90,120 @@ impl BroadcastStrategy {
             _ => None,
         }
     }
+
+    pub fn path(&self) -> Option<&[CommsPublicKey]> {
+        use BroadcastStrategy::*;
+        match self {
+            OnionRoute { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// The score (exclusive) a peer must be above to be eligible for [`BroadcastStrategy::Closest`] selection when a
+/// [`PeerScorer`] is supplied to [`select_peers`]. Matches the neutral (no-history) score of `0.0` minus two
+/// recorded failures, so a single recent failure never excludes a peer, while two failures in quick succession
+/// (before any decay) bring it to exactly this score and exclude it.
+pub const DEFAULT_MIN_PEER_SCORE: f64 = -2.0;
+
+/// Resolves `strategy` against `candidates` (peers the caller has already determined are eligible, e.g. the n
+/// nearest neighbours or all known Communication Node peers), applying `scorer` to bias [`BroadcastStrategy::Closest`]
+/// and [`BroadcastStrategy::Random`] selection when one is available.
+///
+/// `DirectNodeId`, `DirectPublicKey`, `Flood` and `OnionRoute` don't select from a candidate set, so `candidates` is
+/// ignored for those and an empty `Vec` is returned.
+pub fn select_peers(strategy: &BroadcastStrategy, candidates: Vec<NodeId>, scorer: Option<&dyn PeerScorer>) -> Vec<NodeId> {
+    use BroadcastStrategy::*;
+    match strategy {
+        Closest(request) => {
+            let mut candidates = match scorer {
+                Some(scorer) => filter_by_penalty_threshold(candidates, scorer, DEFAULT_MIN_PEER_SCORE),
+                None => candidates,
+            };
+            if let Some(scorer) = scorer {
+                candidates.sort_by(|a, b| {
+                    scorer
+                        .score(b)
+                        .partial_cmp(&scorer.score(a))
+                        .unwrap_or(Ordering::Equal)
+                });
+            }
+            candidates.truncate(request.n);
+            candidates
+        },
+        Random(n) => match scorer {
+            Some(scorer) => weighted_sample(&candidates, scorer, *n).into_iter().cloned().collect(),
+            None => {
+                use rand::seq::SliceRandom;
+                candidates.choose_multiple(&mut rand::rngs::OsRng, *n).cloned().collect()
+            },
+        },
+        DirectNodeId(_) | DirectPublicKey(_) | Flood | OnionRoute { .. } => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tari_crypto::keys::{PublicKey, SecretKey};
+
+    use super::*;
+    use crate::outbound::peer_scorer::DecayingPenaltyScorer;
+
+    fn node_id() -> NodeId {
+        let sk = <CommsPublicKey as PublicKey>::K::random(&mut rand::rngs::OsRng);
+        NodeId::from_public_key(&CommsPublicKey::from_secret_key(&sk))
+    }
+
+    #[test]
+    fn select_peers_closest_excludes_penalised_and_orders_by_score() {
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let good = node_id();
+        let bad = node_id();
+        let excluded = node_id();
+        scorer.record_failure(&bad);
+        scorer.record_failure(&excluded);
+        scorer.record_failure(&excluded);
+        scorer.record_failure(&excluded);
+
+        let strategy = BroadcastStrategy::Closest(BroadcastClosestRequest {
+            n: 2,
+            node_id: NodeId::default(),
+            excluded_peers: Vec::new(),
+        });
+        let selected = select_peers(
+            &strategy,
+            vec![excluded.clone(), bad.clone(), good.clone()],
+            Some(&scorer),
+        );
+
+        assert_eq!(selected, vec![good, bad]);
+    }
+
+    #[test]
+    fn select_peers_closest_without_scorer_just_truncates() {
+        let strategy = BroadcastStrategy::Closest(BroadcastClosestRequest {
+            n: 1,
+            node_id: NodeId::default(),
+            excluded_peers: Vec::new(),
+        });
+        let first = node_id();
+        let selected = select_peers(&strategy, vec![first.clone(), node_id()], None);
+        assert_eq!(selected, vec![first]);
+    }
+
+    #[test]
+    fn select_peers_random_without_scorer_takes_n() {
+        let strategy = BroadcastStrategy::Random(2);
+        let selected = select_peers(&strategy, vec![node_id(), node_id(), node_id()], None);
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn select_peers_ignores_candidates_for_non_selecting_strategies() {
+        let strategy = BroadcastStrategy::Flood;
+        let selected = select_peers(&strategy, vec![node_id()], None);
+        assert!(selected.is_empty());
+    }
 }
@@ -0,0 +1,64 @@
+// Copyright 2019, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Wires the peer manager's own connection-lifecycle events into a [`PeerScorer`], so that peers are scored from
+//! real connection outcomes rather than only from explicit [`record_success`](PeerScorer::record_success)/
+//! [`record_failure`](PeerScorer::record_failure) calls made by broadcast dispatch. Spawned as a task alongside the
+//! connection manager at node startup, the same way [`PolicyConnectionGate`](super::super::connection_gate::
+//! PolicyConnectionGate) is registered with it.
+
+use std::{sync::Arc, time::Duration};
+
+use tari_comms::connection_manager::ConnectionManagerEvent;
+use tokio::sync::broadcast;
+
+use crate::outbound::peer_scorer::PeerScorer;
+
+/// Consumes `events` until the connection manager's event channel closes, feeding successful connections and failed/dropped
+/// connections into `scorer` as delivery successes and failures respectively.
+///
+/// A connection event only tells us that a peer *is* or *is not* reachable, not how long a broadcast to it would
+/// take, so connection successes are recorded with a zero latency; actual broadcast round-trip latency is still
+/// recorded separately by the outbound message dispatcher via [`PeerScorer::record_success`].
+pub async fn feed_connection_events(mut events: broadcast::Receiver<Arc<ConnectionManagerEvent>>, scorer: Arc<dyn PeerScorer>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // A lagged receiver has missed some events but can keep going; a closed sender means the connection
+            // manager has shut down and there's nothing left to feed.
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        match event.as_ref() {
+            ConnectionManagerEvent::PeerConnected(conn) => {
+                scorer.record_success(conn.peer_node_id(), Duration::ZERO);
+            },
+            ConnectionManagerEvent::PeerConnectFailed(node_id, _) => {
+                scorer.record_failure(node_id);
+            },
+            ConnectionManagerEvent::PeerDisconnected(node_id) => {
+                scorer.record_failure(node_id);
+            },
+            _ => {},
+        }
+    }
+}
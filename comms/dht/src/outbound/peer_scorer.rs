@@ -0,0 +1,320 @@
+// Copyright 2019, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Tracks per-peer delivery reliability so that [`select_peers`](super::broadcast_strategy::select_peers) can bias
+//! [`BroadcastStrategy::Closest`](super::broadcast_strategy::BroadcastStrategy::Closest) and
+//! [`BroadcastStrategy::Random`](super::broadcast_strategy::BroadcastStrategy::Random) selection towards peers with a
+//! better delivery history. [`DecayingPenaltyScorer`] is updated from connection/delivery events (see
+//! [`peer_manager_scoring`](super::peer_manager_scoring)) and can be persisted to disk with [`DecayingPenaltyScorer::
+//! save_to_file`] so scores survive a node restart.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tari_comms::peer_manager::node_id::NodeId;
+
+/// Tracks delivery success/failure and latency per peer and combines them into a score used to drive peer selection.
+pub trait PeerScorer: Send + Sync {
+    /// Returns the current score for `peer`. Higher is better; a peer with no recorded history should score as
+    /// neutral (`0.0` penalty).
+    fn score(&self, peer: &NodeId) -> f64;
+
+    /// Records a successful broadcast delivery to `peer`, observed with the given round-trip latency.
+    fn record_success(&self, peer: &NodeId, latency: Duration);
+
+    /// Records a failed broadcast delivery to `peer`.
+    fn record_failure(&self, peer: &NodeId);
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct PeerScoreState {
+    penalty: f64,
+    latency_ms: f64,
+    /// Seconds since the Unix epoch, so the decayed penalty can be recomputed after a restart.
+    updated_at_secs: u64,
+}
+
+impl PeerScoreState {
+    fn decayed_penalty(&self, half_life: Duration, now: SystemTime) -> f64 {
+        let updated_at = UNIX_EPOCH + Duration::from_secs(self.updated_at_secs);
+        let elapsed = now.duration_since(updated_at).unwrap_or_default();
+        if half_life.is_zero() {
+            return self.penalty;
+        }
+        let half_lives_elapsed = elapsed.as_secs_f64() / half_life.as_secs_f64();
+        self.penalty * 0.5_f64.powf(half_lives_elapsed)
+    }
+}
+
+/// A persisted snapshot of one peer's score, suitable for writing to and loading from storage so that scores
+/// survive a node restart.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PersistedPeerScore {
+    pub peer: NodeId,
+    penalty: f64,
+    latency_ms: f64,
+    updated_at_secs: u64,
+}
+
+/// Default [`PeerScorer`] implementation: each failure adds a fixed penalty, each success subtracts one, and the
+/// accumulated penalty decays exponentially towards zero with the configured half-life. The latency passed to
+/// [`PeerScorer::record_success`] is stored and persisted, but it does not affect [`PeerScorer::score`].
+pub struct DecayingPenaltyScorer {
+    half_life: Duration,
+    failure_penalty: f64,
+    success_reward: f64,
+    scores: Mutex<HashMap<NodeId, PeerScoreState>>,
+}
+
+impl DecayingPenaltyScorer {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            failure_penalty: 1.0,
+            success_reward: 1.0,
+            scores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Restores a scorer from a previously-persisted snapshot (see [`Self::export`]).
+    pub fn load(half_life: Duration, persisted: Vec<PersistedPeerScore>) -> Self {
+        let scorer = Self::new(half_life);
+        let mut scores = scorer.scores.lock().expect("peer scorer lock poisoned");
+        for entry in persisted {
+            scores.insert(entry.peer, PeerScoreState {
+                penalty: entry.penalty,
+                latency_ms: entry.latency_ms,
+                updated_at_secs: entry.updated_at_secs,
+            });
+        }
+        drop(scores);
+        scorer
+    }
+
+    /// Exports the current per-peer scores so they can be persisted and restored with [`Self::load`].
+    pub fn export(&self) -> Vec<PersistedPeerScore> {
+        self.scores
+            .lock()
+            .expect("peer scorer lock poisoned")
+            .iter()
+            .map(|(peer, state)| PersistedPeerScore {
+                peer: peer.clone(),
+                penalty: state.penalty,
+                latency_ms: state.latency_ms,
+                updated_at_secs: state.updated_at_secs,
+            })
+            .collect()
+    }
+
+    fn now_secs() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+
+    /// Restores a scorer from the JSON snapshot at `path` (as written by [`Self::save_to_file`]). Returns a fresh,
+    /// empty scorer if `path` does not exist yet, e.g. on first startup.
+    pub fn load_from_file(half_life: Duration, path: &Path) -> Result<Self, PeerScorerPersistenceError> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                let persisted: Vec<PersistedPeerScore> = serde_json::from_slice(&bytes)?;
+                Ok(Self::load(half_life, persisted))
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new(half_life)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the current per-peer scores to `path` as JSON, so they survive a node restart (see
+    /// [`Self::load_from_file`]).
+    pub fn save_to_file(&self, path: &Path) -> Result<(), PeerScorerPersistenceError> {
+        let persisted = self.export();
+        let bytes = serde_json::to_vec(&persisted)?;
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+}
+
+/// An error saving or loading a [`DecayingPenaltyScorer`] snapshot to/from disk.
+#[derive(Debug, thiserror::Error)]
+pub enum PeerScorerPersistenceError {
+    #[error("failed to read or write the peer score snapshot file: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to (de)serialize the peer score snapshot: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl PeerScorer for DecayingPenaltyScorer {
+    fn score(&self, peer: &NodeId) -> f64 {
+        let scores = self.scores.lock().expect("peer scorer lock poisoned");
+        match scores.get(peer) {
+            Some(state) => -state.decayed_penalty(self.half_life, SystemTime::now()),
+            None => 0.0,
+        }
+    }
+
+    fn record_success(&self, peer: &NodeId, latency: Duration) {
+        let mut scores = self.scores.lock().expect("peer scorer lock poisoned");
+        let now = SystemTime::now();
+        let entry = scores.entry(peer.clone()).or_insert(PeerScoreState {
+            penalty: 0.0,
+            latency_ms: 0.0,
+            updated_at_secs: Self::now_secs(),
+        });
+        let decayed = entry.decayed_penalty(self.half_life, now);
+        entry.penalty = (decayed - self.success_reward).max(0.0);
+        entry.latency_ms = latency.as_secs_f64() * 1000.0;
+        entry.updated_at_secs = Self::now_secs();
+    }
+
+    fn record_failure(&self, peer: &NodeId) {
+        let mut scores = self.scores.lock().expect("peer scorer lock poisoned");
+        let now = SystemTime::now();
+        let entry = scores.entry(peer.clone()).or_insert(PeerScoreState {
+            penalty: 0.0,
+            latency_ms: 0.0,
+            updated_at_secs: Self::now_secs(),
+        });
+        let decayed = entry.decayed_penalty(self.half_life, now);
+        entry.penalty = decayed + self.failure_penalty;
+        entry.updated_at_secs = Self::now_secs();
+    }
+}
+
+/// Filters `peers`, removing any whose score (per `scorer`) is at or below `min_score`. Used by
+/// [`BroadcastStrategy::Closest`](super::broadcast_strategy::BroadcastStrategy::Closest) to avoid selecting
+/// consistently unreachable peers.
+pub fn filter_by_penalty_threshold(peers: Vec<NodeId>, scorer: &dyn PeerScorer, min_score: f64) -> Vec<NodeId> {
+    peers.into_iter().filter(|peer| scorer.score(peer) > min_score).collect()
+}
+
+/// Picks `n` peers from `peers` at random, weighted by each peer's inverse penalty (higher score => more likely to be
+/// picked). Used by [`BroadcastStrategy::Random`](super::broadcast_strategy::BroadcastStrategy::Random).
+pub fn weighted_sample<'a>(peers: &'a [NodeId], scorer: &dyn PeerScorer, n: usize) -> Vec<&'a NodeId> {
+    use rand::Rng;
+
+    let mut weighted: Vec<(f64, &NodeId)> = peers
+        .iter()
+        .map(|peer| {
+            // Map score (can be negative) to a strictly positive weight so heavily-penalised peers are still
+            // selectable, just unlikely.
+            let weight = (scorer.score(peer) + 1.0).exp();
+            (weight, peer)
+        })
+        .collect();
+
+    let mut rng = rand::rngs::OsRng;
+    let mut selected = Vec::with_capacity(n.min(weighted.len()));
+    while !weighted.is_empty() && selected.len() < n {
+        let total: f64 = weighted.iter().map(|(w, _)| w).sum();
+        let mut target = rng.gen::<f64>() * total;
+        let mut idx = 0;
+        for (i, (weight, _)) in weighted.iter().enumerate() {
+            target -= weight;
+            if target <= 0.0 {
+                idx = i;
+                break;
+            }
+        }
+        selected.push(weighted.remove(idx).1);
+    }
+    selected
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn neutral_score_for_unknown_peer() {
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let peer = NodeId::default();
+        assert_eq!(scorer.score(&peer), 0.0);
+    }
+
+    #[test]
+    fn failure_lowers_score_and_success_recovers_it() {
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let peer = NodeId::default();
+
+        scorer.record_failure(&peer);
+        let after_failure = scorer.score(&peer);
+        assert!(after_failure < 0.0);
+
+        scorer.record_success(&peer, Duration::from_millis(50));
+        let after_success = scorer.score(&peer);
+        assert!(after_success > after_failure);
+    }
+
+    #[test]
+    fn filters_peers_below_threshold() {
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let bad_peer = NodeId::default();
+        scorer.record_failure(&bad_peer);
+        scorer.record_failure(&bad_peer);
+        scorer.record_failure(&bad_peer);
+
+        let filtered = filter_by_penalty_threshold(vec![bad_peer.clone()], &scorer, -1.0);
+        assert!(filtered.is_empty());
+    }
+
+    #[test]
+    fn export_and_load_round_trip() {
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let peer = NodeId::default();
+        scorer.record_failure(&peer);
+
+        let persisted = scorer.export();
+        let restored = DecayingPenaltyScorer::load(Duration::from_secs(60), persisted);
+        assert!(restored.score(&peer) < 0.0);
+    }
+
+    #[test]
+    fn save_and_load_from_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("peer_scorer_test_{:?}", std::thread::current().id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scores.json");
+
+        let scorer = DecayingPenaltyScorer::new(Duration::from_secs(60));
+        let peer = NodeId::default();
+        scorer.record_failure(&peer);
+        scorer.save_to_file(&path).unwrap();
+
+        let restored = DecayingPenaltyScorer::load_from_file(Duration::from_secs(60), &path).unwrap();
+        assert!(restored.score(&peer) < 0.0);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn load_from_file_missing_path_is_a_fresh_scorer() {
+        let path = std::env::temp_dir().join("peer_scorer_test_does_not_exist.json");
+        fs::remove_file(&path).ok();
+
+        let scorer = DecayingPenaltyScorer::load_from_file(Duration::from_secs(60), &path).unwrap();
+        assert_eq!(scorer.score(&NodeId::default()), 0.0);
+    }
+}